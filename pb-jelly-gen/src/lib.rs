@@ -30,6 +30,7 @@
 //! }
 //! ```
 
+use fs2::FileExt;
 use include_dir::{
     include_dir,
     Dir,
@@ -72,8 +73,25 @@ pub struct GenProtos {
     include_paths: Vec<PathBuf>,
     include_extensions: bool,
     cleanup_out_path: bool,
+    emit_rerun_if_changed: bool,
+    build_client: bool,
+    build_server: bool,
+    protoc_path: Option<PathBuf>,
+    file_descriptor_set_path: Option<PathBuf>,
+    check: bool,
+    type_attributes: Vec<(String, String)>,
+    field_attributes: Vec<(String, String)>,
+    extern_paths: Vec<(String, String)>,
+    venv_cache_dir: Option<PathBuf>,
+    force_venv_rebuild: bool,
 }
 
+/// The version of the `protobuf` pip package we install into the codegen venv. [`resolve_protoc`]
+/// only picks a `protoc` from `PATH` if it reports this version; an explicit
+/// `protoc_path()`/`PROTOC` override and the bundled `protoc` are not checked against it, so
+/// `create_venv` warns instead if the resolved `protoc` doesn't match.
+const PINNED_PROTOBUF_VERSION: &str = "3.21.12";
+
 impl std::default::Default for GenProtos {
     fn default() -> Self {
         let gen_path =
@@ -84,6 +102,20 @@ impl std::default::Default for GenProtos {
         let include_paths = vec![];
         let include_extensions = true;
         let cleanup_out_path = false;
+        // Only emit `cargo:` instructions when Cargo invoked us (it sets `CARGO` for build
+        // scripts, and also for binaries and tests it runs); a process started without Cargo
+        // gets no `cargo:` output by default.
+        let emit_rerun_if_changed = std::env::var_os("CARGO").is_some();
+        let build_client = false;
+        let build_server = false;
+        let protoc_path = None;
+        let file_descriptor_set_path = None;
+        let check = false;
+        let type_attributes = vec![];
+        let field_attributes = vec![];
+        let extern_paths = vec![];
+        let venv_cache_dir = None;
+        let force_venv_rebuild = false;
 
         GenProtos {
             gen_path,
@@ -91,6 +123,17 @@ impl std::default::Default for GenProtos {
             include_paths,
             include_extensions,
             cleanup_out_path,
+            emit_rerun_if_changed,
+            build_client,
+            build_server,
+            protoc_path,
+            file_descriptor_set_path,
+            check,
+            type_attributes,
+            field_attributes,
+            extern_paths,
+            venv_cache_dir,
+            force_venv_rebuild,
         }
     }
 }
@@ -162,9 +205,123 @@ impl GenProtos {
         self
     }
 
+    /// If true, print `cargo:rerun-if-changed` for every discovered `.proto` file and every
+    /// directory in `src_paths`/`include_paths`, plus `cargo:rerun-if-env-changed=PROTOC`, so
+    /// Cargo only re-invokes `build.rs` (and thus `protoc`) when proto sources actually change.
+    ///
+    /// Defaults to true when `CARGO` is set in the environment, i.e. when invoked by Cargo (as a
+    /// `build.rs` is).
+    pub fn emit_rerun_if_changed(mut self, emit: bool) -> GenProtos {
+        self.emit_rerun_if_changed = emit;
+        self
+    }
+
+    /// Generate a client struct for each `service` declared in your protos, with one async method
+    /// per `rpc`. Streaming `rpc`s get methods that take/return a stream type on the streaming side.
+    ///
+    /// Defaults to false.
+    pub fn build_client(mut self, build: bool) -> GenProtos {
+        self.build_client = build;
+        self
+    }
+
+    /// Generate a server trait for each `service` declared in your protos, with one async method
+    /// per `rpc`. Streaming `rpc`s get methods that take/return a stream type on the streaming side.
+    ///
+    /// Defaults to false.
+    pub fn build_server(mut self, build: bool) -> GenProtos {
+        self.build_server = build;
+        self
+    }
+
+    /// Use this exact `protoc` binary instead of resolving one from the `PROTOC` environment
+    /// variable or `PATH`.
+    ///
+    /// Takes priority over `PROTOC` and over any `protoc` found on `PATH`.
+    pub fn protoc_path<P: AsRef<Path>>(mut self, path: P) -> GenProtos {
+        self.protoc_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Write a serialized `FileDescriptorSet` for all compiled protos (including imports, and
+    /// with source info) to `path`. Useful for runtime reflection, dynamic message handling, or
+    /// feeding other tools that consume descriptor sets.
+    pub fn file_descriptor_set_path<P: AsRef<Path>>(mut self, path: P) -> GenProtos {
+        self.file_descriptor_set_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// If true, don't write to `out_path`. Instead generate into a temp directory and diff it
+    /// against `out_path`, failing with the list of mismatched or missing files if they differ.
+    ///
+    /// Useful for projects that check generated code into source control: run with `check(true)`
+    /// in a `cargo test` or CI build step to assert the committed bindings are still current.
+    ///
+    /// Defaults to false.
+    pub fn check(mut self, check: bool) -> GenProtos {
+        self.check = check;
+        self
+    }
+
+    /// Attach an extra `#[...]` attribute to the generated type whose proto path matches
+    /// `proto_path` (e.g. `.mypackage.MyMessage`). Useful for deriving extra traits such as
+    /// `#[derive(serde::Serialize)]` without forking the codegen.
+    pub fn type_attribute<S: AsRef<str>>(mut self, proto_path: S, attribute: S) -> GenProtos {
+        self.type_attributes
+            .push((proto_path.as_ref().to_owned(), attribute.as_ref().to_owned()));
+        self
+    }
+
+    /// Attach an extra `#[...]` attribute to the generated field whose proto path matches
+    /// `proto_path` (e.g. `.mypackage.MyMessage.my_field`).
+    pub fn field_attribute<S: AsRef<str>>(mut self, proto_path: S, attribute: S) -> GenProtos {
+        self.field_attributes
+            .push((proto_path.as_ref().to_owned(), attribute.as_ref().to_owned()));
+        self
+    }
+
+    /// Suppress message and enum generation for `proto_package` and instead emit a `use` alias
+    /// pointing at `rust_path`, so messages defined elsewhere (e.g. a well-known-types crate shared
+    /// across your workspace) are reused instead of re-generated. Services in that package are
+    /// still generated when `build_client`/`build_server` ask for them.
+    pub fn extern_path<S: AsRef<str>>(mut self, proto_package: S, rust_path: S) -> GenProtos {
+        self.extern_paths
+            .push((proto_package.as_ref().to_owned(), rust_path.as_ref().to_owned()));
+        self
+    }
+
+    /// Override the directory used to cache the codegen Python venv across builds. The venv is
+    /// keyed by the pinned protoc version and a hash of the bundled codegen script, so unrelated
+    /// `pb-jelly-gen` versions never share a cache entry.
+    ///
+    /// Defaults to `$OUT_DIR/pb-jelly-gen-cache` when `OUT_DIR` is set (i.e. when running under
+    /// `cargo build`), otherwise a directory under the system temp dir.
+    pub fn venv_cache_dir<P: AsRef<Path>>(mut self, path: P) -> GenProtos {
+        self.venv_cache_dir = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// If true, always rebuild the codegen venv from scratch instead of reusing a cached one.
+    ///
+    /// Defaults to false.
+    pub fn force_venv_rebuild(mut self, force: bool) -> GenProtos {
+        self.force_venv_rebuild = force;
+        self
+    }
+
     /// Consumes the builder and generates Rust bindings to your proto files.
     pub fn gen_protos(self) {
-        let output = self.gen_protos_helper();
+        let check = self.check;
+        let gen_path = self.gen_path.clone();
+        let file_descriptor_set_path = self.file_descriptor_set_path.clone();
+        let check_dir = if check {
+            Some(tempfile::tempdir().expect("Failed to create temp dir for `check`"))
+        } else {
+            None
+        };
+        let out_path = check_dir.as_ref().map_or_else(|| gen_path.clone(), |dir| dir.path().to_owned());
+
+        let output = self.gen_protos_helper(&out_path);
 
         if !output.status.success() {
             dbg!(output.status.code());
@@ -173,39 +330,58 @@ impl GenProtos {
             panic!("Failed to generate Rust bindings to proto files!")
         }
 
+        if let Some(check_dir) = &check_dir {
+            let mut mismatches = diff_gen_trees(check_dir.path(), &gen_path);
+            if let Some(descriptor_set_path) = &file_descriptor_set_path {
+                let descriptor_set_out = check_dir
+                    .path()
+                    .join(descriptor_set_path.file_name().expect("file_descriptor_set_path must name a file"));
+                mismatches.extend(diff_file(&descriptor_set_out, descriptor_set_path));
+            }
+            if !mismatches.is_empty() {
+                panic!(
+                    "generated code in `{}` is out of date with the `.proto` sources:\n{}",
+                    gen_path.display(),
+                    mismatches.join("\n")
+                );
+            }
+        }
+
         dbg!("Protos Generated Successfully");
     }
 }
 
 // Private functions
 impl GenProtos {
-    fn gen_protos_helper(self) -> Output {
-        // Clean up root generated directory
-        if self.cleanup_out_path && self.gen_path.exists() && self.gen_path.is_dir() {
-            dbg!("Cleaning up existing gen path", &self.gen_path);
-            fs::remove_dir_all(&self.gen_path).expect("Failed to clean");
-        }
+    fn gen_protos_helper(self, out_path: &Path) -> Output {
+        if !self.check {
+            // Clean up root generated directory
+            if self.cleanup_out_path && out_path.exists() && out_path.is_dir() {
+                dbg!("Cleaning up existing gen path", &out_path);
+                fs::remove_dir_all(out_path).expect("Failed to clean");
+            }
 
-        // Re-create essential files
-        if !self.gen_path.exists() {
-            dbg!("Creating gen path", &self.gen_path);
-            fs::create_dir_all(&self.gen_path).expect("Failed to create dir");
+            // Re-create essential files
+            if !out_path.exists() {
+                dbg!("Creating gen path", &out_path);
+                fs::create_dir_all(out_path).expect("Failed to create dir");
+            }
         }
-        let temp_dir = self.create_temp_files().expect("Failed to package codegen script");
+        let codegen_dir = self.ensure_codegen_source().expect("Failed to prepare codegen script");
 
         // Generate extensions in python (prereq for rust codegen)
-        self.gen_extensions(&temp_dir);
+        self.gen_extensions(&codegen_dir);
         // Generate Rust protos
-        self.gen_rust_protos(temp_dir)
+        self.gen_rust_protos(&codegen_dir, out_path)
     }
 
-    fn gen_extensions(&self, temp_dir: &tempfile::TempDir) {
-        let mut protoc_cmd = Command::new("protoc");
+    fn gen_extensions(&self, codegen_dir: &Path) {
+        let mut protoc_cmd = Command::new(resolve_protoc(&self.protoc_path));
         protoc_cmd.arg("-I");
-        protoc_cmd.arg(temp_dir.path());
+        protoc_cmd.arg(codegen_dir);
         protoc_cmd.arg("--python_out");
-        protoc_cmd.arg(temp_dir.path().join("proto"));
-        protoc_cmd.arg(temp_dir.path().join("rust").join("extensions.proto"));
+        protoc_cmd.arg(codegen_dir.join("proto"));
+        protoc_cmd.arg(codegen_dir.join("rust").join("extensions.proto"));
         dbg!(&protoc_cmd);
         let status = protoc_cmd
             .status()
@@ -213,61 +389,70 @@ impl GenProtos {
         assert!(status.success());
     }
 
-    fn create_venv(&self, temp_dir: &tempfile::TempDir) -> PathBuf {
-        // parse protoc --version
-        let protoc_version = {
-            let output = Command::new("protoc")
-                .arg("--version")
-                .output()
-                .expect("Failed to get protoc version (is protoc installed?)");
-            assert!(output.status.success());
-            let version = String::from_utf8(output.stdout).expect("Unable to parse protoc --version output in utf8");
-            let mut version_parts = version.split_whitespace();
-            assert_eq!(version_parts.next(), Some("libprotoc"));
-            version_parts
-                .next()
-                .expect("Version not found in parsed protoc --version output")
-                .to_string()
-        };
+    /// Create (or reuse) the codegen venv at a stable, content-addressed location under
+    /// `venv_cache_dir`, keyed by [`PINNED_PROTOBUF_VERSION`] and a hash of the bundled codegen
+    /// script. Skips recreation and reinstalling `pip`/`protobuf`/pb-jelly when a venv already
+    /// exists for that key, unless `force_venv_rebuild` is set.
+    fn create_venv(&self, codegen_dir: &Path) -> PathBuf {
+        warn_on_protoc_version_mismatch(&resolve_protoc(&self.protoc_path));
 
-        // Create venv
-        let venv = temp_dir.path().join(".codegen_venv");
-        let status = Command::new(if cfg!(windows) { "python.exe" } else { "python3" })
-            .args(&["-m", "venv"])
-            .arg(&venv)
-            .status()
-            .expect("Failed to create venv");
-        assert!(status.success(), "Failed to create venv");
+        let venv = self.cache_root().join("venv").join(self.cache_key());
         let bin_dir = venv.join(if cfg!(windows) { "Scripts" } else { "bin" });
+        let ready_marker = venv.join(".ready");
+
+        // Hold an exclusive lock across the whole check-then-create section: two `build.rs`
+        // invocations sharing `venv_cache_dir` (e.g. parallel workspace members) must not both
+        // observe "not ready" and race to `remove_dir_all`/rebuild the same venv.
+        with_cache_lock(&self.cache_root(), &self.cache_key(), "venv", || {
+            if !self.force_venv_rebuild && ready_marker.exists() {
+                dbg!("Reusing cached codegen venv", &venv);
+                return bin_dir.clone();
+            }
+
+            if venv.exists() {
+                dbg!("Rebuilding stale codegen venv", &venv);
+                fs::remove_dir_all(&venv).expect("Failed to clean stale codegen venv");
+            }
 
-        // pip install --upgrade pip protobuf=={version}
-        let mut cmd = Command::new(bin_dir.join(if cfg!(windows) { "python.exe" } else { "python" }));
-        cmd.args(&[
-            "-m",
-            "pip",
-            "install",
-            "--upgrade",
-            "pip",
-            &format!("protobuf=={}", protoc_version),
-        ]);
-        dbg!(&cmd);
-        let status = cmd.status().expect("Failed to pip install protobuf");
-        assert!(status.success(), "Failed to pip install protobuf");
-
-        // pip install -e .
-        let mut cmd = Command::new(bin_dir.join(if cfg!(windows) { "pip.exe" } else { "pip" }));
-        cmd.args(&["install", "-e"]);
-        cmd.arg(temp_dir.path());
-        dbg!(&cmd);
-        let status = cmd.status().expect("Failed to pip install pb-jelly");
-        assert!(status.success(), "Failed to pip install pb-jelly");
-
-        bin_dir
-    }
-
-    fn gen_rust_protos(&self, temp_dir: tempfile::TempDir) -> Output {
+            // Create venv
+            let status = Command::new(if cfg!(windows) { "python.exe" } else { "python3" })
+                .args(["-m", "venv"])
+                .arg(&venv)
+                .status()
+                .expect("Failed to create venv");
+            assert!(status.success(), "Failed to create venv");
+
+            // pip install --upgrade pip protobuf=={version}
+            let mut cmd = Command::new(bin_dir.join(if cfg!(windows) { "python.exe" } else { "python" }));
+            cmd.args([
+                "-m",
+                "pip",
+                "install",
+                "--upgrade",
+                "pip",
+                &format!("protobuf=={}", PINNED_PROTOBUF_VERSION),
+            ]);
+            dbg!(&cmd);
+            let status = cmd.status().expect("Failed to pip install protobuf");
+            assert!(status.success(), "Failed to pip install protobuf");
+
+            // pip install -e .
+            let mut cmd = Command::new(bin_dir.join(if cfg!(windows) { "pip.exe" } else { "pip" }));
+            cmd.args(["install", "-e"]);
+            cmd.arg(codegen_dir);
+            dbg!(&cmd);
+            let status = cmd.status().expect("Failed to pip install pb-jelly");
+            assert!(status.success(), "Failed to pip install pb-jelly");
+
+            fs::write(&ready_marker, b"").expect("Failed to write venv cache marker");
+
+            bin_dir.clone()
+        })
+    }
+
+    fn gen_rust_protos(&self, codegen_dir: &Path, out_path: &Path) -> Output {
         let new_path = {
-            let venv_bin = self.create_venv(&temp_dir);
+            let venv_bin = self.create_venv(codegen_dir);
             let mut path: Vec<_> = std::env::split_paths(&std::env::var_os("PATH").unwrap()).collect();
             path.insert(0, venv_bin);
             std::env::join_paths(path).unwrap()
@@ -275,7 +460,7 @@ impl GenProtos {
         dbg!(&new_path);
 
         // Create protoc cmd in the venv
-        let mut protoc_cmd = Command::new("protoc");
+        let mut protoc_cmd = Command::new(resolve_protoc(&self.protoc_path));
         protoc_cmd.env("PATH", new_path);
 
         // Directories that contain protos
@@ -288,10 +473,9 @@ impl GenProtos {
 
         // If we want to include our `extensions.proto` file for Rust extentions
         if self.include_extensions {
-            let ext_path = temp_dir.path();
             protoc_cmd.arg("-I");
-            protoc_cmd.arg(ext_path);
-            dbg!(ext_path);
+            protoc_cmd.arg(codegen_dir);
+            dbg!(codegen_dir);
         }
 
         // Include any protos from our include paths
@@ -303,24 +487,77 @@ impl GenProtos {
 
         // Set the Rust out path
         protoc_cmd.arg("--rust_out");
-        protoc_cmd.arg(&self.gen_path);
+        protoc_cmd.arg(out_path);
+
+        // Tell codegen.py whether to emit gRPC client/server stubs for `service` definitions
+        let mut rust_opts = vec![];
+        if self.build_client {
+            rust_opts.push("build_client=true");
+        }
+        if self.build_server {
+            rust_opts.push("build_server=true");
+        }
+        // Written to a fresh temp file (not the cached `codegen_dir`) since these options are
+        // specific to this `gen_protos()` call, not to the shared, content-addressed codegen venv.
+        // Kept alive (not dropped) until `protoc` has run, below.
+        let config_file;
+        let config_opt;
+        if !self.type_attributes.is_empty() || !self.field_attributes.is_empty() || !self.extern_paths.is_empty() {
+            config_file = tempfile::Builder::new()
+                .prefix("pb-jelly-codegen-config")
+                .suffix(".json")
+                .tempfile()
+                .expect("Failed to create codegen config temp file");
+            fs::write(config_file.path(), self.codegen_config_json()).expect("Failed to write codegen config");
+            config_opt = format!("config={}", config_file.path().display());
+            rust_opts.push(&config_opt);
+        }
+        if !rust_opts.is_empty() {
+            protoc_cmd.arg("--rust_opt");
+            protoc_cmd.arg(rust_opts.join(","));
+        }
+
+        // Emit a FileDescriptorSet alongside the generated code, if requested. In `check` mode
+        // `out_path` is already redirected to a temp `check_dir` (see `gen_protos`); reuse that
+        // same redirection here so `check` never mutates the real descriptor set file either.
+        if let Some(descriptor_set_path) = &self.file_descriptor_set_path {
+            let descriptor_set_out = if self.check {
+                out_path.join(descriptor_set_path.file_name().expect("file_descriptor_set_path must name a file"))
+            } else {
+                descriptor_set_path.clone()
+            };
+            protoc_cmd.arg("--descriptor_set_out");
+            protoc_cmd.arg(descriptor_set_out);
+            protoc_cmd.arg("--include_imports");
+            protoc_cmd.arg("--include_source_info");
+        }
 
         // Get paths of our Protos
-        let proto_paths = self
+        let proto_paths: Vec<PathBuf> = self
             .src_paths
             .iter()
-            .map(|path| {
+            .flat_map(|path| {
                 WalkDir::new(path)
                     .into_iter()
                     .filter_map(Result::ok)
                     .filter(|file| file.path().extension().unwrap_or_default() == "proto")
                     .map(|file| file.into_path())
             })
-            .flatten();
+            .collect();
+
+        if self.emit_rerun_if_changed {
+            for path in self.src_paths.iter().chain(self.include_paths.iter()) {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+            for path in &proto_paths {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+            println!("cargo:rerun-if-env-changed=PROTOC");
+        }
 
         // Set each proto file as an argument
         dbg!("Proto paths");
-        for path in proto_paths {
+        for path in &proto_paths {
             dbg!(&path);
             protoc_cmd.arg(path);
         }
@@ -331,46 +568,163 @@ impl GenProtos {
             .expect("something went wrong in running protoc to generate Rust bindings 🤮")
     }
 
-    /// We bundle all non-Rust, but necessary files into a static CODEGEN blob. When we run the codegen script,
-    /// we recreate these in a temp directory `/tmp/codegen` that is cleaned up after.
-    fn create_temp_files(&self) -> std::io::Result<tempfile::TempDir> {
-        let temp_dir = tempfile::Builder::new().prefix("codegen").tempdir()?;
+    /// Serialize `type_attribute`/`field_attribute`/`extern_path` into the small JSON blob
+    /// `codegen.py` reads to decide what attributes or `use` aliases to emit per message/field.
+    fn codegen_config_json(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.replace('\\', "\\\\").replace('"', "\\\"")
+        }
+        fn pairs_json(pairs: &[(String, String)], key_name: &str, value_name: &str) -> String {
+            let entries: Vec<String> = pairs
+                .iter()
+                .map(|(key, value)| format!("{{\"{}\":\"{}\",\"{}\":\"{}\"}}", key_name, escape(key), value_name, escape(value)))
+                .collect();
+            format!("[{}]", entries.join(","))
+        }
+
+        format!(
+            "{{\"type_attributes\":{},\"field_attributes\":{},\"extern_paths\":{}}}",
+            pairs_json(&self.type_attributes, "proto_path", "attribute"),
+            pairs_json(&self.field_attributes, "proto_path", "attribute"),
+            pairs_json(&self.extern_paths, "proto_package", "rust_path"),
+        )
+    }
 
-        fn create_temp_files_helper(dir: &Dir, temp_dir: &tempfile::TempDir) -> std::io::Result<()> {
-            for file in dir.files() {
-                let blob_path = file.path();
-                let abs_path = temp_dir.path().join(blob_path);
+    /// The root directory under which we cache both the extracted codegen script and the venv
+    /// built from it, keyed by [`GenProtos::cache_key`].
+    fn cache_root(&self) -> PathBuf {
+        self.venv_cache_dir.clone().unwrap_or_else(default_cache_root)
+    }
 
-                let mut abs_file = fs::OpenOptions::new().write(true).create_new(true).open(&abs_path)?;
-                abs_file.write_all(file.contents())?;
+    /// A cache key that changes whenever the pinned protoc/protobuf version or the bundled
+    /// codegen script changes, so stale caches are never reused across incompatible versions.
+    fn cache_key(&self) -> String {
+        format!("protoc-{}-codegen-{:016x}", PINNED_PROTOBUF_VERSION, hash_codegen())
+    }
 
-                #[cfg(not(windows))]
-                {
-                    let mut permissions = abs_file.metadata()?.permissions();
-                    permissions.set_mode(0o777);
-                    drop(abs_file);
+    /// We bundle all non-Rust, but necessary files into a static CODEGEN blob. We extract these
+    /// once into a stable, content-addressed directory under `cache_root()`, and reuse that
+    /// extraction (along with the venv built from it, see `create_venv`) across builds instead of
+    /// re-extracting and reinstalling on every single `gen_protos()` call.
+    fn ensure_codegen_source(&self) -> std::io::Result<PathBuf> {
+        let source_dir = self.cache_root().join("src").join(self.cache_key());
+        let ready_marker = source_dir.join(".ready");
+
+        // Same race as `create_venv`: hold the lock across the check-then-extract section so two
+        // builds sharing `venv_cache_dir` can't have one `remove_dir_all` the source out from
+        // under the other's in-progress extraction.
+        with_cache_lock(&self.cache_root(), &self.cache_key(), "src", || {
+            if ready_marker.exists() {
+                dbg!("Reusing cached codegen source", &source_dir);
+                return Ok(source_dir.clone());
+            }
 
-                    // Set permissions of the file so it is executable
-                    fs::set_permissions(&abs_path, permissions)?;
-                }
+            if source_dir.exists() {
+                fs::remove_dir_all(&source_dir)?;
             }
+            fs::create_dir_all(&source_dir)?;
 
-            for dir in dir.dirs() {
-                let blob_path = dir.path();
-                let abs_path = temp_dir.path().join(blob_path);
-                fs::create_dir(&abs_path)?;
+            extract_dir(&CODEGEN, &source_dir)?;
 
-                create_temp_files_helper(dir, temp_dir)?;
-            }
+            fs::write(&ready_marker, b"")?;
+            Ok(source_dir.clone())
+        })
+    }
+}
+
+/// Recursively extract `dir` into `dest`. On non-Windows every extracted file is made executable
+/// (mode `0o777`), since the bundled blob carries no file modes of its own.
+fn extract_dir(dir: &Dir, dest: &Path) -> std::io::Result<()> {
+    for file in dir.files() {
+        let abs_path = dest.join(file.path());
+
+        let mut abs_file = fs::OpenOptions::new().write(true).create_new(true).open(&abs_path)?;
+        abs_file.write_all(file.contents())?;
 
-            Ok(())
+        #[cfg(not(windows))]
+        {
+            let mut permissions = abs_file.metadata()?.permissions();
+            permissions.set_mode(0o777);
+            drop(abs_file);
+
+            // Set permissions of the file so it is executable
+            fs::set_permissions(&abs_path, permissions)?;
         }
-        create_temp_files_helper(&CODEGEN, &temp_dir)?;
+    }
+
+    for sub_dir in dir.dirs() {
+        let abs_path = dest.join(sub_dir.path());
+        fs::create_dir(&abs_path)?;
+
+        extract_dir(sub_dir, dest)?;
+    }
+
+    Ok(())
+}
+
+/// Run `f` while holding an exclusive, advisory file lock scoped to `(cache_key, what)` (e.g.
+/// `what = "venv"` vs `"src"`, so the venv and source caches don't contend on the same lock).
+/// Serializes the check-then-create sections of `create_venv`/`ensure_codegen_source` across
+/// concurrent `build.rs` invocations sharing `cache_root` (e.g. parallel workspace members),
+/// so one can't `remove_dir_all` a cache entry the other just decided to reuse or rebuild.
+///
+/// The lock file lives in `cache_root/locks`, separate from the cache entries themselves, so
+/// holding it open is unaffected by `remove_dir_all`-ing a stale `venv`/`src` entry.
+fn with_cache_lock<T>(cache_root: &Path, cache_key: &str, what: &str, f: impl FnOnce() -> T) -> T {
+    let locks_dir = cache_root.join("locks");
+    fs::create_dir_all(&locks_dir).expect("Failed to create cache lock dir");
+
+    let lock_path = locks_dir.join(format!("{cache_key}.{what}.lock"));
+    let lock_file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .expect("Failed to open cache lock file");
+    lock_file.lock_exclusive().expect("Failed to acquire cache lock");
+
+    let result = f();
+
+    // Dropping `lock_file` would also release the lock, but release explicitly so a mistaken
+    // future `return` added above the drop point can't accidentally hold it longer than `f`.
+    lock_file.unlock().expect("Failed to release cache lock");
+    result
+}
 
-        Ok(temp_dir)
+/// Where we cache the extracted codegen script and the venv built from it, when `venv_cache_dir`
+/// isn't set: `$OUT_DIR/pb-jelly-gen-cache` under `cargo build`, else a directory under the
+/// system temp dir.
+fn default_cache_root() -> PathBuf {
+    match std::env::var_os("OUT_DIR") {
+        Some(out_dir) => PathBuf::from(out_dir).join("pb-jelly-gen-cache"),
+        None => std::env::temp_dir().join("pb-jelly-gen-cache"),
     }
 }
 
+/// Hash the bundled `CODEGEN` blob's paths and contents, so the venv/source cache key changes
+/// whenever the codegen script this crate ships is updated.
+fn hash_codegen() -> u64 {
+    use std::hash::{
+        Hash,
+        Hasher,
+    };
+
+    fn hash_dir(dir: &Dir, hasher: &mut impl Hasher) {
+        for file in dir.files() {
+            file.path().hash(hasher);
+            file.contents().hash(hasher);
+        }
+        for sub_dir in dir.dirs() {
+            sub_dir.path().hash(hasher);
+            hash_dir(sub_dir, hasher);
+        }
+    }
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    hash_dir(&CODEGEN, &mut hasher);
+    hasher.finish()
+}
+
 /// Helper function to get the path of the current Cargo.toml
 ///
 /// Get the environment value of `CARGO_MANIFEST_DIR` and converts it into a `PathBuf`
@@ -379,3 +733,247 @@ fn get_cargo_manifest_path() -> std::io::Result<PathBuf> {
     let path_str = std::env::var("CARGO_MANIFEST_DIR").map_err(|_| std::io::ErrorKind::NotFound)?;
     Ok(PathBuf::from(path_str))
 }
+
+/// Figure out which `protoc` binary to invoke, in priority order:
+///
+/// 1. `protoc_path`, i.e. `GenProtos::protoc_path()`, if set.
+/// 2. The `PROTOC` environment variable, if set.
+/// 3. `protoc` on `PATH`, if it reports [`PINNED_PROTOBUF_VERSION`].
+/// 4. A `protoc` bundled with this crate, built from the `bundled-protoc` feature.
+///
+/// Only path 3 is version-checked here: it is taken only when `protoc --version` reports the
+/// version of the `protobuf` pip package `create_venv` installs. Paths 1 and 2 are explicit
+/// overrides, and path 4 returns whatever `protoc` the `bundled-protoc` feature builds; none of
+/// them is checked. If the resolved `protoc` doesn't match, `create_venv` prints a warning (see
+/// [`warn_on_protoc_version_mismatch`]) rather than silently proceeding.
+fn resolve_protoc(protoc_path: &Option<PathBuf>) -> PathBuf {
+    if let Some(path) = protoc_path {
+        return path.clone();
+    }
+
+    if let Some(path) = std::env::var_os("PROTOC") {
+        return PathBuf::from(path);
+    }
+
+    if protoc_on_path_matches_pinned_version() {
+        return PathBuf::from("protoc");
+    }
+
+    #[cfg(feature = "bundled-protoc")]
+    {
+        return protobuf_src::protoc();
+    }
+
+    #[cfg(not(feature = "bundled-protoc"))]
+    panic!(
+        "couldn't find a `protoc` matching the pinned version {}; set `PROTOC`, call \
+         `GenProtos::protoc_path()`, or enable the `bundled-protoc` feature",
+        PINNED_PROTOBUF_VERSION
+    );
+}
+
+/// Diff `generated` against `existing` file-by-file, returning a description of every file that's
+/// missing or out of date in `existing`, plus every file in `existing` no longer produced by
+/// codegen. An empty result means the two trees match.
+fn diff_gen_trees(generated: &Path, existing: &Path) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    for entry in WalkDir::new(generated).into_iter().filter_map(Result::ok) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel_path = entry
+            .path()
+            .strip_prefix(generated)
+            .expect("walked entry is under `generated`");
+        let matches = fs::read(entry.path()).and_then(|generated_bytes| {
+            fs::read(existing.join(rel_path)).map(|existing_bytes| generated_bytes == existing_bytes)
+        });
+        match matches {
+            Ok(true) => {}
+            Ok(false) => mismatches.push(format!("{} (out of date)", rel_path.display())),
+            Err(_) => mismatches.push(format!("{} (missing)", rel_path.display())),
+        }
+    }
+
+    for entry in WalkDir::new(existing).into_iter().filter_map(Result::ok) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel_path = entry.path().strip_prefix(existing).expect("walked entry is under `existing`");
+        if !generated.join(rel_path).exists() {
+            mismatches.push(format!("{} (stale, no longer generated)", rel_path.display()));
+        }
+    }
+
+    mismatches
+}
+
+/// Diff a single generated file (`generated`) against its on-disk counterpart (`existing`),
+/// returning a one-element description if they differ or `existing` is missing, `None` if they
+/// match. Used for `file_descriptor_set_path` in `check` mode, where [`diff_gen_trees`]'s
+/// whole-directory walk doesn't apply.
+fn diff_file(generated: &Path, existing: &Path) -> Option<String> {
+    let matches = fs::read(generated)
+        .and_then(|generated_bytes| fs::read(existing).map(|existing_bytes| generated_bytes == existing_bytes));
+    match matches {
+        Ok(true) => None,
+        Ok(false) => Some(format!("{} (out of date)", existing.display())),
+        Err(_) => Some(format!("{} (missing)", existing.display())),
+    }
+}
+
+/// Checks whether `protoc` on `PATH` exists and reports [`PINNED_PROTOBUF_VERSION`].
+fn protoc_on_path_matches_pinned_version() -> bool {
+    protoc_version(Path::new("protoc")).as_deref() == Some(PINNED_PROTOBUF_VERSION)
+}
+
+/// Run `protoc --version` and pull out the version number, e.g. `"3.21.12"` from
+/// `"libprotoc 3.21.12"`. Returns `None` if `protoc` can't be run or its output can't be parsed.
+fn protoc_version(protoc: &Path) -> Option<String> {
+    let output = Command::new(protoc).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let version = from_utf8(&output.stdout).ok()?;
+    version.split_whitespace().nth(1).map(str::to_owned)
+}
+
+/// `resolve_protoc` only checks the `PATH` candidate against the pinned version; the
+/// `PROTOC`/`protoc_path()` overrides and the bundled `protoc` are used as they are, so a caller
+/// can point `protoc_path()` or `PROTOC` at any `protoc` they like. Warn (rather than silently
+/// proceeding) when the resolved `protoc` doesn't report [`PINNED_PROTOBUF_VERSION`], since a
+/// mismatch reintroduces exactly the
+/// protoc/python-protobuf wire-format disagreement this pinning exists to avoid.
+fn warn_on_protoc_version_mismatch(protoc: &Path) {
+    match protoc_version(protoc) {
+        Some(version) if version != PINNED_PROTOBUF_VERSION => {
+            eprintln!(
+                "warning: `protoc` at `{}` reports version {version}, but pb-jelly-gen pins \
+                 `protobuf=={PINNED_PROTOBUF_VERSION}` in its codegen venv; generated Rust and \
+                 Python may disagree on wire format. Use a `protoc` matching {PINNED_PROTOBUF_VERSION}, \
+                 or drop `protoc_path()`/`PROTOC` to let pb-jelly-gen resolve one itself.",
+                protoc.display()
+            );
+        }
+        // Matches the pinned version, or we couldn't determine its version at all -- in the
+        // latter case `gen_rust_protos` will surface a clearer error when it actually runs `protoc`.
+        Some(_) | None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_gen_trees_identical_trees_have_no_mismatches() {
+        let generated = tempfile::tempdir().unwrap();
+        let existing = tempfile::tempdir().unwrap();
+        fs::write(generated.path().join("foo.rs"), b"pub struct Foo;").unwrap();
+        fs::write(existing.path().join("foo.rs"), b"pub struct Foo;").unwrap();
+
+        assert!(diff_gen_trees(generated.path(), existing.path()).is_empty());
+    }
+
+    #[test]
+    fn diff_gen_trees_reports_out_of_date_file() {
+        let generated = tempfile::tempdir().unwrap();
+        let existing = tempfile::tempdir().unwrap();
+        fs::write(generated.path().join("foo.rs"), b"pub struct Foo;").unwrap();
+        fs::write(existing.path().join("foo.rs"), b"pub struct Bar;").unwrap();
+
+        let mismatches = diff_gen_trees(generated.path(), existing.path());
+        assert_eq!(mismatches, vec!["foo.rs (out of date)".to_owned()]);
+    }
+
+    #[test]
+    fn diff_gen_trees_reports_missing_file() {
+        let generated = tempfile::tempdir().unwrap();
+        let existing = tempfile::tempdir().unwrap();
+        fs::write(generated.path().join("foo.rs"), b"pub struct Foo;").unwrap();
+
+        let mismatches = diff_gen_trees(generated.path(), existing.path());
+        assert_eq!(mismatches, vec!["foo.rs (missing)".to_owned()]);
+    }
+
+    #[test]
+    fn diff_gen_trees_reports_stale_file() {
+        let generated = tempfile::tempdir().unwrap();
+        let existing = tempfile::tempdir().unwrap();
+        fs::write(existing.path().join("old.rs"), b"pub struct Old;").unwrap();
+
+        let mismatches = diff_gen_trees(generated.path(), existing.path());
+        assert_eq!(mismatches, vec!["old.rs (stale, no longer generated)".to_owned()]);
+    }
+
+    #[test]
+    fn diff_file_matching_contents_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let generated = dir.path().join("generated.bin");
+        let existing = dir.path().join("existing.bin");
+        fs::write(&generated, b"descriptor bytes").unwrap();
+        fs::write(&existing, b"descriptor bytes").unwrap();
+
+        assert_eq!(diff_file(&generated, &existing), None);
+    }
+
+    #[test]
+    fn diff_file_reports_out_of_date_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let generated = dir.path().join("generated.bin");
+        let existing = dir.path().join("existing.bin");
+        fs::write(&generated, b"new bytes").unwrap();
+        fs::write(&existing, b"old bytes").unwrap();
+
+        assert_eq!(diff_file(&generated, &existing), Some(format!("{} (out of date)", existing.display())));
+    }
+
+    #[test]
+    fn diff_file_reports_missing_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let generated = dir.path().join("generated.bin");
+        let existing = dir.path().join("existing.bin");
+        fs::write(&generated, b"descriptor bytes").unwrap();
+
+        assert_eq!(diff_file(&generated, &existing), Some(format!("{} (missing)", existing.display())));
+    }
+
+    #[test]
+    fn codegen_config_json_serializes_each_option_kind() {
+        let config = GenProtos::builder()
+            .type_attribute(".pkg.Msg", "#[derive(serde::Serialize)]")
+            .field_attribute(".pkg.Msg.field", "#[serde(default)]")
+            .extern_path(".pkg.Other", "crate::other::Other")
+            .codegen_config_json();
+
+        assert_eq!(
+            config,
+            "{\"type_attributes\":[{\"proto_path\":\".pkg.Msg\",\"attribute\":\"#[derive(serde::Serialize)]\"}],\
+             \"field_attributes\":[{\"proto_path\":\".pkg.Msg.field\",\"attribute\":\"#[serde(default)]\"}],\
+             \"extern_paths\":[{\"proto_package\":\".pkg.Other\",\"rust_path\":\"crate::other::Other\"}]}"
+        );
+    }
+
+    #[test]
+    fn codegen_config_json_escapes_quotes_and_backslashes() {
+        let config = GenProtos::builder()
+            .type_attribute(".pkg.Msg", r#"#[path = "weird\path"]"#)
+            .codegen_config_json();
+
+        assert_eq!(
+            config,
+            "{\"type_attributes\":[{\"proto_path\":\".pkg.Msg\",\"attribute\":\"#[path = \\\"weird\\\\path\\\"]\"}],\
+             \"field_attributes\":[],\"extern_paths\":[]}"
+        );
+    }
+
+    #[test]
+    fn codegen_config_json_empty_by_default() {
+        let config = GenProtos::builder().codegen_config_json();
+        assert_eq!(
+            config,
+            "{\"type_attributes\":[],\"field_attributes\":[],\"extern_paths\":[]}"
+        );
+    }
+}